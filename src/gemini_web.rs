@@ -3,15 +3,20 @@ use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
 
 use mime::Mime;
 use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use url::Url;
 
+use crate::identity::IdentityStore;
+use crate::input::InputAnswers;
+use crate::robots::RobotsCache;
+
 pub type GeminiGraph = Graph<Url, usize>;
 
 #[derive(Default, Serialize, Deserialize)]
@@ -20,6 +25,10 @@ pub struct GeminiWeb {
     pub visited: HashSet<Url>,
     url_node_ids: HashMap<Url, NodeIndex>,
     pub url_response: HashMap<Url, GeminiResponse>,
+    pub identities: IdentityStore,
+    pub input_answers: InputAnswers,
+    pub robots: RobotsCache,
+    pub robots_skipped: usize,
 }
 
 impl GeminiWeb {
@@ -29,6 +38,10 @@ impl GeminiWeb {
             visited: HashSet::new(),
             url_node_ids: HashMap::new(),
             url_response: HashMap::new(),
+            identities: IdentityStore::new(),
+            input_answers: InputAnswers::new(),
+            robots: RobotsCache::new(),
+            robots_skipped: 0,
         }
     }
 
@@ -64,26 +77,11 @@ impl GeminiWeb {
         registered_urls.difference(&self.visited).cloned().collect()
     }
 
-    pub fn to_dot(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
-        let path = path.as_ref();
-
-        // Piping dot representation of graph to graphviz and writing the output to an image
-        let mut dot_process = Command::new("dot")
-            .arg(format!(
-                "-T{}",
-                path.extension()
-                    .ok_or("path doesn't have extension")?
-                    .to_str()
-                    .unwrap()
-            ))
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-        let mut dot_process_stdin = dot_process.stdin.take().expect("Failed to get stdin");
-        let graph = self.graph.clone(); // TODO: could be slow
-        std::thread::spawn(move || {
-            let graph_dot = Dot::with_attr_getters(
-                &graph,
+    fn dot_string(&self) -> String {
+        format!(
+            "{:?}",
+            Dot::with_attr_getters(
+                &self.graph,
                 &[Config::EdgeNoLabel],
                 &|_, _| String::new(),
                 &|_, (_, url)| {
@@ -94,9 +92,37 @@ impl GeminiWeb {
                         url.domain().unwrap(),
                     )
                 },
-            );
+            )
+        )
+    }
+
+    pub fn to_dot(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .ok_or("path doesn't have extension")?
+            .to_str()
+            .unwrap();
+
+        // A `.dot` extension means the caller wants the Graphviz source itself,
+        // no need to shell out for rasterization.
+        if extension == "dot" {
+            let mut dot_file = File::create(path)?;
+            dot_file.write_all(self.dot_string().as_bytes())?;
+            return Ok(());
+        }
+
+        // Piping dot representation of graph to graphviz and writing the output to an image
+        let mut dot_process = Command::new("dot")
+            .arg(format!("-T{extension}"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut dot_process_stdin = dot_process.stdin.take().expect("Failed to get stdin");
+        let dot_string = self.dot_string();
+        std::thread::spawn(move || {
             dot_process_stdin
-                .write_all(format!("{:?}", graph_dot).as_bytes())
+                .write_all(dot_string.as_bytes())
                 .expect("Counldn't write to stdin");
         });
         let output = dot_process.wait_with_output()?;
@@ -104,6 +130,173 @@ impl GeminiWeb {
         dot_file.write_all(&output.stdout[..])?;
         Ok(())
     }
+
+    /// Emit the link graph as GraphML (nodes carry `url`/`mime`, edges carry
+    /// the link-count `weight` tracked by [`GeminiWeb::add_urls`]), loadable
+    /// into Gephi/Cytoscape without a `dot` subprocess.
+    pub fn to_graphml(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        out.push_str("  <key id=\"url\" for=\"node\" attr.name=\"url\" attr.type=\"string\"/>\n");
+        out.push_str("  <key id=\"mime\" for=\"node\" attr.name=\"mime\" attr.type=\"string\"/>\n");
+        out.push_str("  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"int\"/>\n");
+        out.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+        for node_index in self.graph.node_indices() {
+            let url = &self.graph[node_index];
+            out.push_str(&format!(
+                "    <node id=\"n{}\">\n      <data key=\"url\">{}</data>\n",
+                node_index.index(),
+                escape_xml(url.as_str()),
+            ));
+            if let Some(mime) = self.mime_of(url) {
+                out.push_str(&format!("      <data key=\"mime\">{}</data>\n", escape_xml(&mime)));
+            }
+            out.push_str("    </node>\n");
+        }
+        for edge in self.graph.edge_references() {
+            out.push_str(&format!(
+                "    <edge source=\"n{}\" target=\"n{}\">\n      <data key=\"weight\">{}</data>\n    </edge>\n",
+                edge.source().index(),
+                edge.target().index(),
+                edge.weight(),
+            ));
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        let mut file = File::create(path)?;
+        file.write_all(out.as_bytes())?;
+        Ok(())
+    }
+
+    /// Emit the link graph as node/edge JSON, for programmatic post-processing.
+    pub fn to_json(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let nodes: Vec<JsonNode> = self
+            .graph
+            .node_indices()
+            .map(|node_index| {
+                let url = &self.graph[node_index];
+                JsonNode {
+                    id: node_index.index(),
+                    url: url.to_string(),
+                    mime: self.mime_of(url),
+                }
+            })
+            .collect();
+        let edges: Vec<JsonEdge> = self
+            .graph
+            .edge_references()
+            .map(|edge| JsonEdge {
+                source: edge.source().index(),
+                target: edge.target().index(),
+                weight: *edge.weight(),
+            })
+            .collect();
+        let json = JsonGraph { nodes, edges };
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &json)?;
+        Ok(())
+    }
+
+    fn mime_of(&self, url: &Url) -> Option<String> {
+        match &self.url_response.get(url)?.header {
+            GeminiHeader::Success(mime) => Some(mime.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Render every visited `text/gemini` document to HTML under `dir`, mirroring
+    /// each URL's host/path, so the crawl result is browsable offline. Links
+    /// between two visited pages are rewritten to relative local paths.
+    pub fn export_html(&self, dir: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let dir = dir.as_ref();
+        for (url, response) in &self.url_response {
+            let GeminiBody::Gemtext(text) = &response.body else {
+                continue;
+            };
+            let out_path = dir.join(local_html_path(url));
+            let page_dir = out_path.parent().ok_or("html output path has no parent")?;
+            std::fs::create_dir_all(page_dir)?;
+
+            let html = text.to_html(|link| {
+                if self.url_response.contains_key(link) {
+                    relative_path(page_dir, &dir.join(local_html_path(link)))
+                } else {
+                    link.to_string()
+                }
+            });
+            let mut html_file = File::create(&out_path)?;
+            html_file.write_all(
+                format!("<!DOCTYPE html>\n<html>\n<body>\n{html}</body>\n</html>\n").as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonNode {
+    id: usize,
+    url: String,
+    mime: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonEdge {
+    source: usize,
+    target: usize,
+    weight: usize,
+}
+
+#[derive(Serialize)]
+struct JsonGraph {
+    nodes: Vec<JsonNode>,
+    edges: Vec<JsonEdge>,
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Where `export_html` writes `url`'s page, mirroring its host/path: a path
+/// ending in `/` (or empty) becomes `host/path/index.html`, otherwise `.html`
+/// is appended to the last segment. URLs that differ only by query string
+/// (as produced by answering an Input prompt) get distinct files by folding
+/// a short hash of the query into the file name.
+fn local_html_path(url: &Url) -> PathBuf {
+    use sha2::{Digest, Sha256};
+
+    let mut path = PathBuf::from(url.domain().unwrap_or("unknown-host"));
+    let url_path = url.path().trim_start_matches('/');
+    let query_suffix = url
+        .query()
+        .map(|query| format!("-{:.8}", hex::encode(Sha256::digest(query.as_bytes()))))
+        .unwrap_or_default();
+    if url_path.is_empty() || url.path().ends_with('/') {
+        path.push(url_path);
+        path.push(format!("index{query_suffix}.html"));
+    } else {
+        path.push(format!("{url_path}{query_suffix}.html"));
+    }
+    path
+}
+
+/// A `/`-separated relative path from `from_dir` to `to_file`.
+fn relative_path(from_dir: &Path, to_file: &Path) -> String {
+    let from: Vec<_> = from_dir.components().collect();
+    let to: Vec<_> = to_file.components().collect();
+    let common = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+    let mut rel = PathBuf::new();
+    for _ in common..from.len() {
+        rel.push("..");
+    }
+    for component in &to[common..] {
+        rel.push(component.as_os_str());
+    }
+    rel.to_string_lossy().replace('\\', "/")
 }
 
 pub fn parse_body_urls(base_url: &Url, body: &str) -> Vec<Url> {
@@ -169,30 +362,59 @@ impl FromStr for GeminiHeader {
     }
 }
 
+/// A response body, either parsed gemtext or, for any other `Success` MIME
+/// type, the raw bytes as received.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum GeminiBody {
+    Gemtext(GeminiText),
+    Binary {
+        #[serde(
+            serialize_with = "serialize_mime",
+            deserialize_with = "deserialize_mime"
+        )]
+        mime: Mime,
+        bytes: Vec<u8>,
+    },
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GeminiResponse {
     url: Url,
     pub header: GeminiHeader,
-    pub body: GeminiText,
+    pub body: GeminiBody,
+    /// Label of the client identity presented to obtain this response, if the
+    /// server required one.
+    pub identity: Option<String>,
 }
 
 impl GeminiResponse {
-    pub fn new(response: &str, url: &Url) -> Result<GeminiResponse, Box<dyn Error>> {
-        let (header, body) = response
-            .split_once("\r\n")
-            .ok_or("Gemini response invalid format")?;
-        let header: GeminiHeader = header.parse()?;
-        let body = GeminiText::new(body, url)?;
+    /// Build a response from an already-parsed header and the bytes that
+    /// follow it, parsing gemtext for `text/gemini` and keeping everything
+    /// else as an opaque byte body.
+    pub fn new(header: GeminiHeader, body: &[u8], url: &Url) -> Result<GeminiResponse, Box<dyn Error>> {
+        let body = match &header {
+            GeminiHeader::Success(mime) if mime.essence_str() == "text/gemini" => {
+                GeminiBody::Gemtext(GeminiText::new(std::str::from_utf8(body)?, url)?)
+            }
+            GeminiHeader::Success(mime) => GeminiBody::Binary {
+                mime: mime.clone(),
+                bytes: body.to_vec(),
+            },
+            _ => GeminiBody::Gemtext(GeminiText::default()),
+        };
         Ok(GeminiResponse {
             url: url.clone(),
             header,
             body,
+            identity: None,
         })
     }
 
     pub fn gemini_urls(&self) -> Vec<Url> {
-        self.body
-            .0
+        let GeminiBody::Gemtext(text) = &self.body else {
+            return Vec::new();
+        };
+        text.0
             .iter()
             .filter_map(|s| match s {
                 GeminiTextStatement::Link(u, _) if u.scheme() == "gemini" => Some(u),
@@ -203,6 +425,19 @@ impl GeminiResponse {
     }
 }
 
+/// Write `bytes` under `dir`, named after their SHA-256 hex digest, so
+/// repeated downloads of identical content are stored once.
+pub fn cache_bytes(dir: impl AsRef<Path>, bytes: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+    use sha2::{Digest, Sha256};
+    std::fs::create_dir_all(&dir)?;
+    let digest = Sha256::digest(bytes);
+    let path = dir.as_ref().join(hex::encode(digest));
+    if !path.exists() {
+        std::fs::write(&path, bytes)?;
+    }
+    Ok(path)
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct GeminiText(Vec<GeminiTextStatement>);
 
@@ -212,6 +447,7 @@ enum GeminiTextStatement {
     Link(Url, String),
     ListItem(String),
     Header(GeminiTextHeaderLevel, String),
+    Preformatted(String),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -232,9 +468,10 @@ impl GeminiText {
             let line = line.trim().to_string();
             if line == "```" {
                 in_pre = !in_pre;
+                continue;
             }
             if in_pre {
-                text.0.push(Line(line));
+                text.0.push(Preformatted(line));
                 continue;
             }
             let statement = match line.as_bytes() {
@@ -253,6 +490,71 @@ impl GeminiText {
         }
         Ok(text)
     }
+
+    /// Render as HTML, grouping consecutive list items into a single `<ul>`
+    /// and consecutive preformatted lines into a single `<pre>`.
+    /// `resolve_link` turns a linked [`Url`] into the `href` to emit.
+    pub fn to_html(&self, resolve_link: impl Fn(&Url) -> String) -> String {
+        use GeminiTextHeaderLevel::*;
+        use GeminiTextStatement::*;
+        let mut html = String::new();
+        let mut in_list = false;
+        let mut in_pre = false;
+        for statement in &self.0 {
+            if in_list && !matches!(statement, ListItem(_)) {
+                html.push_str("</ul>\n");
+                in_list = false;
+            }
+            if in_pre && !matches!(statement, Preformatted(_)) {
+                html.push_str("</pre>\n");
+                in_pre = false;
+            }
+            match statement {
+                Header(L1, s) => html.push_str(&format!("<h1>{}</h1>\n", escape_html(s))),
+                Header(L2, s) => html.push_str(&format!("<h2>{}</h2>\n", escape_html(s))),
+                Header(L3, s) => html.push_str(&format!("<h3>{}</h3>\n", escape_html(s))),
+                ListItem(s) => {
+                    if !in_list {
+                        html.push_str("<ul>\n");
+                        in_list = true;
+                    }
+                    html.push_str(&format!("<li>{}</li>\n", escape_html(s)));
+                }
+                Preformatted(s) => {
+                    if !in_pre {
+                        html.push_str("<pre>\n");
+                        in_pre = true;
+                    }
+                    html.push_str(&escape_html(s));
+                    html.push('\n');
+                }
+                Link(url, label) => {
+                    let href = resolve_link(url);
+                    let text = if label.is_empty() { url.as_str() } else { label.as_str() };
+                    html.push_str(&format!(
+                        "<a href=\"{}\">{}</a>\n",
+                        escape_html(&href),
+                        escape_html(text)
+                    ));
+                }
+                Line(s) => html.push_str(&format!("<p>{}</p>\n", escape_html(s))),
+            }
+        }
+        if in_list {
+            html.push_str("</ul>\n");
+        }
+        if in_pre {
+            html.push_str("</pre>\n");
+        }
+        html
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 use std::fmt;
@@ -261,15 +563,60 @@ impl fmt::Display for GeminiText {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use GeminiTextHeaderLevel::*;
         use GeminiTextStatement::*;
-        self.0
-            .iter()
-            .try_for_each(|statement| match statement {
-                Line(s) => writeln!(f, "{}", s),
-                Link(url, label) => writeln!(f, "=> {} ({})", url, label),
-                ListItem(s) => writeln!(f, "* {}", s),
-                Header(L1, s) => writeln!(f, "# {}", s),
-                Header(L2, s) => writeln!(f, "## {}", s),
-                Header(L3, s) => writeln!(f, "### {}", s),
-            })
+        let mut in_pre = false;
+        for statement in &self.0 {
+            let is_pre = matches!(statement, Preformatted(_));
+            if is_pre != in_pre {
+                writeln!(f, "```")?;
+                in_pre = is_pre;
+            }
+            match statement {
+                Line(s) => writeln!(f, "{}", s)?,
+                Preformatted(s) => writeln!(f, "{}", s)?,
+                Link(url, label) => writeln!(f, "=> {} ({})", url, label)?,
+                ListItem(s) => writeln!(f, "* {}", s)?,
+                Header(L1, s) => writeln!(f, "# {}", s)?,
+                Header(L2, s) => writeln!(f, "## {}", s)?,
+                Header(L3, s) => writeln!(f, "### {}", s)?,
+            }
+        }
+        if in_pre {
+            writeln!(f, "```")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html_special_characters() {
+        assert_eq!(escape_html("<a> & \"b\""), "&lt;a&gt; &amp; &quot;b&quot;");
+    }
+
+    #[test]
+    fn renders_headers_lists_and_preformatted_blocks() {
+        let base_url = Url::parse("gemini://example.com/").unwrap();
+        let text = GeminiText::new(
+            "# Title\n* one\n* two\n```\nraw <code>\n```\nplain text",
+            &base_url,
+        )
+        .unwrap();
+        let html = text.to_html(|url| url.to_string());
+        assert_eq!(
+            html,
+            "<h1>Title</h1>\n<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+<pre>\nraw &lt;code&gt;\n</pre>\n<p>plain text</p>\n"
+        );
+    }
+
+    #[test]
+    fn resolves_links_through_the_callback() {
+        let base_url = Url::parse("gemini://example.com/").unwrap();
+        let text = GeminiText::new("=> /other.gmi Other page", &base_url).unwrap();
+        let html = text.to_html(|_url| "other.html".to_string());
+        assert_eq!(html, "<a href=\"other.html\">Other page</a>\n");
     }
 }
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use rcgen::generate_simple_self_signed;
+use rustls::{Certificate, PrivateKey};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// A client certificate identity the crawler can present when a capsule
+/// replies with an Auth (60-69) status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiIdentity {
+    pub label: String,
+    key_pem: String,
+    cert_pem: String,
+}
+
+impl GeminiIdentity {
+    pub fn new(label: impl Into<String>, key_pem: String, cert_pem: String) -> GeminiIdentity {
+        GeminiIdentity {
+            label: label.into(),
+            key_pem,
+            cert_pem,
+        }
+    }
+
+    /// Generate an ephemeral self-signed identity, good enough for capsules
+    /// that merely require "any" client certificate.
+    pub fn generate_ephemeral(label: impl Into<String>) -> Result<GeminiIdentity, Box<dyn Error>> {
+        let cert = generate_simple_self_signed(vec!["gemini-crawler".to_string()])?;
+        Ok(GeminiIdentity::new(
+            label,
+            cert.serialize_private_key_pem(),
+            cert.serialize_pem()?,
+        ))
+    }
+
+    /// Decode the stored PEM pair into the `(cert chain, private key)` shape
+    /// `rustls::ClientConfig::with_client_auth_cert` expects.
+    pub fn certified_key(&self) -> Result<(Vec<Certificate>, PrivateKey), Box<dyn Error>> {
+        let certs = rustls_pemfile::certs(&mut self.cert_pem.as_bytes())?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = rustls_pemfile::pkcs8_private_keys(&mut self.key_pem.as_bytes())?
+            .into_iter()
+            .map(PrivateKey)
+            .next()
+            .ok_or("identity PEM doesn't contain a private key")?;
+        Ok((certs, key))
+    }
+}
+
+/// Per-path-prefix registry of client identities, consulted whenever a `60`
+/// Auth response is received for a URL.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct IdentityStore {
+    by_path_prefix: HashMap<String, GeminiIdentity>,
+}
+
+impl IdentityStore {
+    pub fn new() -> IdentityStore {
+        IdentityStore::default()
+    }
+
+    pub fn register(&mut self, path_prefix: impl Into<String>, identity: GeminiIdentity) {
+        self.by_path_prefix.insert(path_prefix.into(), identity);
+    }
+
+    /// The most specific registered identity whose path prefix matches `url`, if any.
+    pub fn matching(&self, url: &Url) -> Option<&GeminiIdentity> {
+        self.by_path_prefix
+            .iter()
+            .filter(|(prefix, _)| url.path().starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, identity)| identity)
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Registry of canned answers for Input (status `10`/`11`) prompts, looked up
+/// either by the exact prompt text or by the URL path prefix that asked it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InputAnswers {
+    by_prompt: HashMap<String, String>,
+    by_path_prefix: HashMap<String, String>,
+}
+
+impl InputAnswers {
+    pub fn new() -> InputAnswers {
+        InputAnswers::default()
+    }
+
+    pub fn register_for_prompt(&mut self, prompt: impl Into<String>, answer: impl Into<String>) {
+        self.by_prompt.insert(prompt.into(), answer.into());
+    }
+
+    pub fn register_for_path(&mut self, path_prefix: impl Into<String>, answer: impl Into<String>) {
+        self.by_path_prefix.insert(path_prefix.into(), answer.into());
+    }
+
+    /// The configured answer for this prompt, preferring an exact prompt match
+    /// over the most specific matching path prefix.
+    pub fn answer(&self, url: &Url, prompt: &str) -> Option<&str> {
+        self.by_prompt
+            .get(prompt)
+            .or_else(|| {
+                self.by_path_prefix
+                    .iter()
+                    .filter(|(prefix, _)| url.path().starts_with(prefix.as_str()))
+                    .max_by_key(|(prefix, _)| prefix.len())
+                    .map(|(_, answer)| answer)
+            })
+            .map(String::as_str)
+    }
+}
+
+/// Build the URL to re-request after answering an Input prompt, percent-encoding
+/// `answer` into the query component the way the Gemini spec expects.
+pub fn url_with_answer(url: &Url, answer: &str) -> Url {
+    let mut answered_url = url.clone();
+    let encoded = utf8_percent_encode(answer, NON_ALPHANUMERIC).to_string();
+    answered_url.set_query(Some(&encoded));
+    answered_url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_prompt_match_wins_over_path_prefix() {
+        let mut answers = InputAnswers::new();
+        answers.register_for_path("/search", "by-path");
+        answers.register_for_prompt("Query?", "by-prompt");
+        let url = Url::parse("gemini://example.com/search").unwrap();
+        assert_eq!(answers.answer(&url, "Query?"), Some("by-prompt"));
+    }
+
+    #[test]
+    fn falls_back_to_the_most_specific_path_prefix() {
+        let mut answers = InputAnswers::new();
+        answers.register_for_path("/search", "shallow");
+        answers.register_for_path("/search/advanced", "deep");
+        let url = Url::parse("gemini://example.com/search/advanced").unwrap();
+        assert_eq!(answers.answer(&url, "Query?"), Some("deep"));
+    }
+
+    #[test]
+    fn percent_encodes_the_answer_into_the_query() {
+        let url = Url::parse("gemini://example.com/search").unwrap();
+        let answered = url_with_answer(&url, "a b&c");
+        assert_eq!(answered.query(), Some("a%20b%26c"));
+    }
+}
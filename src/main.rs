@@ -1,15 +1,10 @@
-use std::cell::RefCell;
 use std::error::Error;
 use std::fs::File;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::collections::VecDeque;
 
 use async_recursion::async_recursion;
-use futures::prelude::*;
-use futures::stream::FuturesUnordered;
-use native_tls::TlsConnector;
-use petgraph::graph::NodeIndex;
+use rustls::ServerName;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
@@ -17,16 +12,32 @@ use tokio::time::timeout;
 use url::Url;
 
 pub mod gemini_web;
+pub mod identity;
+pub mod input;
+pub mod robots;
+pub mod tofu;
 
-use gemini_web::{GeminiHeader, GeminiResponse, GeminiWeb};
+use gemini_web::{GeminiBody, GeminiHeader, GeminiResponse, GeminiWeb};
+use identity::{GeminiIdentity, IdentityStore};
+use input::InputAnswers;
+use robots::RobotsRules;
+use tofu::{TofuStore, TofuVerifier};
 
 const TIMEOUT: Duration = Duration::from_secs(1);
 const MAX_REDIRECT: usize = 256;
+const MAX_INPUT_ATTEMPTS: usize = 8;
+const KNOWN_HOSTS_PATH: &str = "known_hosts.bincode";
+const CACHE_DIR: &str = "cache";
 
 #[async_recursion]
 async fn gemini_get_recursion(
     url: &Url,
     redirect_count: usize,
+    input_attempt_count: usize,
+    tofu_store: Arc<Mutex<TofuStore>>,
+    identities: Arc<IdentityStore>,
+    presented_identity: Option<GeminiIdentity>,
+    input_answers: Arc<InputAnswers>,
 ) -> Result<GeminiResponse, Box<dyn Error>> {
     if redirect_count > MAX_REDIRECT {
         return Err("Max redirect {MAX_REDIRECT} reached".into());
@@ -34,73 +45,140 @@ async fn gemini_get_recursion(
     let domain = url.domain().unwrap();
     let domain_port = domain.to_owned() + ":1965";
     // Setup SSL
-    let stream = timeout(TIMEOUT, TcpStream::connect(domain_port)).await??;
-    let cx = TlsConnector::builder()
-        .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
-        // library says it uses the default system certs but doesn't work for me
-        .danger_accept_invalid_certs(true)
-        .build()?;
-    let cx = tokio_native_tls::TlsConnector::from(cx);
+    let stream = timeout(TIMEOUT, TcpStream::connect(&domain_port)).await??;
+    let cx = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(TofuVerifier::new(
+            tofu_store.clone(),
+            domain_port,
+        )));
+    let cx = match &presented_identity {
+        Some(identity) => {
+            let (certs, key) = identity.certified_key()?;
+            cx.with_client_auth_cert(certs, key)?
+        }
+        None => cx.with_no_client_auth(),
+    };
+    let cx = tokio_rustls::TlsConnector::from(Arc::new(cx));
+    let server_name = ServerName::try_from(domain)?;
     // Connect to base url and query the gemini page
-    let mut stream = timeout(TIMEOUT, cx.connect(domain, stream)).await??;
+    let mut stream = timeout(TIMEOUT, cx.connect(server_name, stream)).await??;
     timeout(
         TIMEOUT,
         stream.write_all((url.to_string() + "\r\n").as_bytes()),
     )
     .await??;
-    // TODO: parse header in a buf instead of trying to put the whole response in a string
-    // (some response contain binary data like images but still have a valid header)
-    let mut response = String::new();
-    timeout(TIMEOUT, stream.read_to_string(&mut response)).await??;
+    // Read only the header line first so we know what we're dealing with
+    // before deciding whether to pull the (possibly large) body into memory.
+    let mut raw = Vec::new();
+    let mut chunk = [0u8; 512];
+    let header_end = loop {
+        if let Some(pos) = raw.windows(2).position(|w| w == b"\r\n") {
+            break pos;
+        }
+        let n = timeout(TIMEOUT, stream.read(&mut chunk)).await??;
+        if n == 0 {
+            return Err("Gemini response invalid format".into());
+        }
+        raw.extend_from_slice(&chunk[..n]);
+    };
+    let header: GeminiHeader = std::str::from_utf8(&raw[..header_end])?.parse()?;
+    let mut body = raw.split_off(header_end + 2);
+    if matches!(header, GeminiHeader::Success(_)) {
+        timeout(TIMEOUT, stream.read_to_end(&mut body)).await??;
+    }
+    let body = &body;
 
-    let response = GeminiResponse::new(&response, url)?;
+    let mut response = GeminiResponse::new(header, body, url)?;
+    response.identity = presented_identity.as_ref().map(|i| i.label.clone());
     use GeminiHeader::*;
     match response.header {
-        Success(ref mime) if mime.essence_str() == "text/gemini" => Ok(response),
-        Success(mime) => Err(format!("invalid MIME {mime}").into()),
+        Success(_) => Ok(response),
         Redirect(url) => {
             eprintln!("Following redirect to {url}");
-            gemini_get_recursion(&url, redirect_count + 1).await
+            gemini_get_recursion(
+                &url,
+                redirect_count + 1,
+                input_attempt_count,
+                tofu_store,
+                identities,
+                presented_identity,
+                input_answers,
+            )
+            .await
+        }
+        Auth(_) if presented_identity.is_none() => {
+            let identity = match identities.matching(url) {
+                Some(identity) => identity.clone(),
+                None => {
+                    eprintln!("No identity registered for {url}, generating an ephemeral one");
+                    GeminiIdentity::generate_ephemeral(url.to_string())?
+                }
+            };
+            eprintln!("Presenting client certificate for {url}");
+            gemini_get_recursion(
+                url,
+                redirect_count,
+                input_attempt_count,
+                tofu_store,
+                identities.clone(),
+                Some(identity),
+                input_answers,
+            )
+            .await
+        }
+        Input(ref prompt) if input_attempt_count < MAX_INPUT_ATTEMPTS => {
+            match input_answers.answer(url, prompt) {
+                Some(answer) => {
+                    let answered_url = input::url_with_answer(url, answer);
+                    eprintln!("Answering input prompt for {url}");
+                    gemini_get_recursion(
+                        &answered_url,
+                        redirect_count,
+                        input_attempt_count + 1,
+                        tofu_store,
+                        identities,
+                        presented_identity,
+                        input_answers,
+                    )
+                    .await
+                }
+                None => Err(format!("{url} prompted for input (\"{prompt}\") and no answer is registered").into()),
+            }
         }
         _ => Err(format!("invalid header type {:?}", response.header).into()),
     }
 }
 
-async fn gemini_get(url: &Url) -> Result<GeminiResponse, Box<dyn Error>> {
-    gemini_get_recursion(url, 0).await
+async fn gemini_get(
+    url: &Url,
+    tofu_store: Arc<Mutex<TofuStore>>,
+    identities: Arc<IdentityStore>,
+    input_answers: Arc<InputAnswers>,
+) -> Result<GeminiResponse, Box<dyn Error>> {
+    gemini_get_recursion(url, 0, 0, tofu_store, identities, None, input_answers).await
 }
 
-#[async_recursion(?Send)]
-async fn visit_url_recursion(
-    base_url: Url,
-    base_node_id: NodeIndex,
-    web: Rc<RefCell<GeminiWeb>>,
-    depth: usize,
-) -> Result<(), Box<dyn Error>> {
-    // tokio::time::interval is annoying because putting it in a RefCell causes runtime crash
-    tokio::time::sleep(Duration::from_millis(1000)).await;
-    if depth == 0 || web.borrow_mut().try_visit(&base_url) {
-        return Ok(());
-    }
-    eprintln!("Visiting {}", base_url);
-    let response = gemini_get(&base_url).await?;
-
-    web.borrow_mut().url_response.insert(base_url.clone(), response.clone());
-    let urls = response.gemini_urls();
-    let node_ids = web.borrow_mut().add_urls(base_node_id, &urls);
-
-    let mut fs = urls
-        .iter()
-        .zip(node_ids)
-        .map(|(url, node_id)| visit_url_recursion(url.clone(), node_id, web.clone(), depth - 1))
-        .collect::<FuturesUnordered<_>>();
-    while let Some(r) = fs.next().await {
-        match r {
-            Ok(_response) => (),
-            Err(e) => eprintln!("Visit url error: {}", e),
-        }
+/// Fetch and parse `robots.txt` for `url`'s authority, treating any failure
+/// (no such file, connection error, ...) as "nothing disallowed".
+async fn fetch_robots_rules(
+    url: &Url,
+    tofu_store: Arc<Mutex<TofuStore>>,
+    identities: Arc<IdentityStore>,
+    input_answers: Arc<InputAnswers>,
+) -> RobotsRules {
+    let robots_url = match url.join("/robots.txt") {
+        Ok(u) => u,
+        Err(_) => return RobotsRules::default(),
+    };
+    let response = match gemini_get(&robots_url, tofu_store, identities, input_answers).await {
+        Ok(r) => r,
+        Err(_) => return RobotsRules::default(),
+    };
+    match &response.body {
+        GeminiBody::Gemtext(text) => RobotsRules::parse(&text.to_string()),
+        GeminiBody::Binary { bytes, .. } => RobotsRules::parse(&String::from_utf8_lossy(bytes)),
     }
-    Ok(())
 }
 
 use tokio::sync::mpsc;
@@ -109,20 +187,30 @@ const CHANNEL_LEN: usize = 10;
 async fn visit_url(
     mut web: GeminiWeb,
     base_url: Url,
+    tofu_store: Arc<Mutex<TofuStore>>,
 ) -> Result<GeminiWeb, Box<dyn Error>> {
-    // let web = Rc::new(RefCell::new(web));
     let base_node_id = web.add_node(&base_url);
-    // visit_url_recursion(base_url, base_node_id, web.clone(), depth).await?;
-    // Ok(web.take()) // FIXME: understand why into_inner() doesn't work here
+    let identities = Arc::new(web.identities.clone());
+    let input_answers = Arc::new(web.input_answers.clone());
 
     let (url_tx, mut url_rx) = mpsc::channel(CHANNEL_LEN);
     let (response_tx, mut response_rx) = mpsc::channel(CHANNEL_LEN);
     url_tx.send((base_url.clone(), base_node_id)).await?;
 
+    let querier_tofu_store = tofu_store.clone();
+    let querier_identities = identities.clone();
+    let querier_input_answers = input_answers.clone();
     let _querier = tokio::spawn(async move {
         while let Some((url, node_id)) = url_rx.recv().await {
             eprintln!("Visiting {}", url);
-            let response = match gemini_get(&url).await {
+            let response = match gemini_get(
+                &url,
+                querier_tofu_store.clone(),
+                querier_identities.clone(),
+                querier_input_answers.clone(),
+            )
+            .await
+            {
                 Ok(r) => r,
                 Err(e) => {
                     eprintln!("Error gemini_get for {}: {}", url, e);
@@ -136,12 +224,36 @@ async fn visit_url(
 
     while let Some((url, node_id, response)) = response_rx.recv().await {
         web.visited.insert(url.clone());
-        let urls = response.gemini_urls();
-        let urls: Vec<Url> = urls.iter().filter(|u| !web.visited.contains(u)).cloned().collect();
+        if let GeminiBody::Binary { bytes, .. } = &response.body {
+            if let Err(e) = gemini_web::cache_bytes(CACHE_DIR, bytes) {
+                eprintln!("Error caching body for {}: {}", url, e);
+            }
+        }
+        let mut urls: Vec<Url> = response
+            .gemini_urls()
+            .into_iter()
+            .filter(|u| !web.visited.contains(u))
+            .collect();
+        for u in &urls {
+            if !web.robots.has(u) {
+                let rules = fetch_robots_rules(
+                    u,
+                    tofu_store.clone(),
+                    identities.clone(),
+                    input_answers.clone(),
+                )
+                .await;
+                web.robots.record(u, rules);
+            }
+        }
+        let before = urls.len();
+        urls.retain(|u| !web.robots.is_disallowed(u));
+        web.robots_skipped += before - urls.len();
         let node_ids = web.add_urls(node_id, &urls);
         for (u, node_id) in urls.iter().zip(node_ids) {
             url_tx.send_timeout((u.clone(), node_id), Duration::from_secs(1)).await.unwrap();
         }
+        web.url_response.insert(url, response);
     }
 
     Ok(web)
@@ -155,21 +267,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
         Ok(reader) => bincode::deserialize_from(reader)?,
         _ => GeminiWeb::new(),
     };
+    let tofu_store = Arc::new(Mutex::new(TofuStore::load(KNOWN_HOSTS_PATH)));
     let mut unvisited_urls = web.unvisited();
     if unvisited_urls.is_empty() {
         unvisited_urls = vec![Url::parse(BASE_URL)?];
     }
     for unvisited_url in unvisited_urls {
         println!("Trying unvisited url: {}", unvisited_url);
-        web = visit_url(web, unvisited_url).await?;
+        web = visit_url(web, unvisited_url, tofu_store.clone()).await?;
     }
 
     // println!("Node count: {}", graph.node_count());
     // println!("Edge count: {}", graph.edge_count());
+    println!("URLs skipped due to robots.txt: {}", web.robots_skipped);
 
     let web_file = File::create("web.bincode")?;
     bincode::serialize_into(web_file, &web)?;
+    tofu_store.lock().unwrap().save(KNOWN_HOSTS_PATH)?;
 
     web.to_dot("web.svg")?;
+    web.to_graphml("web.graphml")?;
+    web.to_json("web.json")?;
+    web.export_html("html")?;
     Ok(())
 }
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The `Disallow:` prefixes that apply to us, collected from the `User-agent:
+/// *` and `User-agent: indexer` groups of a capsule's `robots.txt`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RobotsRules {
+    disallow: Vec<String>,
+}
+
+const RELEVANT_AGENTS: [&str; 2] = ["*", "indexer"];
+
+impl RobotsRules {
+    pub fn parse(body: &str) -> RobotsRules {
+        let mut disallow = Vec::new();
+        // A group is one or more consecutive `User-agent:` lines, so membership
+        // is the union of every agent named before the first `Disallow:` that
+        // follows them, not just whichever `User-agent:` line came last.
+        let mut group_agents: Vec<&str> = Vec::new();
+        let mut group_open = false;
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if let Some(agent) = line.strip_prefix("User-agent:") {
+                if !group_open {
+                    group_agents.clear();
+                    group_open = true;
+                }
+                group_agents.push(agent.trim());
+            } else if let Some(path) = line.strip_prefix("Disallow:") {
+                group_open = false;
+                let path = path.trim();
+                if !path.is_empty() && group_agents.iter().any(|a| RELEVANT_AGENTS.contains(a)) {
+                    disallow.push(path.to_string());
+                }
+            }
+        }
+        RobotsRules { disallow }
+    }
+
+    pub fn is_disallowed(&self, path: &str) -> bool {
+        self.disallow.iter().any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
+
+/// Per-authority cache of parsed robots rules, keyed by `host:port` so it
+/// survives serialization alongside the rest of [`crate::gemini_web::GeminiWeb`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RobotsCache {
+    by_authority: HashMap<String, RobotsRules>,
+}
+
+impl RobotsCache {
+    pub fn new() -> RobotsCache {
+        RobotsCache::default()
+    }
+
+    pub fn has(&self, url: &Url) -> bool {
+        self.by_authority.contains_key(&authority(url))
+    }
+
+    pub fn record(&mut self, url: &Url, rules: RobotsRules) {
+        self.by_authority.insert(authority(url), rules);
+    }
+
+    pub fn is_disallowed(&self, url: &Url) -> bool {
+        self.by_authority
+            .get(&authority(url))
+            .is_some_and(|rules| rules.is_disallowed(url.path()))
+    }
+}
+
+fn authority(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.domain().unwrap_or(""),
+        url.port_or_known_default().unwrap_or(1965)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disallows_paths_in_a_relevant_group() {
+        let rules = RobotsRules::parse("User-agent: *\nDisallow: /private");
+        assert!(rules.is_disallowed("/private/notes.gmi"));
+        assert!(!rules.is_disallowed("/public"));
+    }
+
+    #[test]
+    fn ignores_groups_for_unrelated_agents() {
+        let rules = RobotsRules::parse("User-agent: googlebot\nDisallow: /private");
+        assert!(!rules.is_disallowed("/private"));
+    }
+
+    #[test]
+    fn unions_agents_sharing_a_group_instead_of_last_line_wins() {
+        let rules = RobotsRules::parse("User-agent: *\nUser-agent: googlebot\nDisallow: /private");
+        assert!(rules.is_disallowed("/private"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, Error as TlsError, ServerName};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A pinned leaf certificate fingerprint for a single `host:port`, as trusted
+/// on first contact or on the first contact after the previous pin expired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KnownHost {
+    fingerprint: [u8; 32],
+    not_after: i64,
+}
+
+/// Trust-on-first-use store: remembers the leaf certificate fingerprint seen
+/// for each authority and refuses silent swaps, the way `known_hosts` does
+/// for SSH and the way Gemini clients in the wild do for this exact purpose.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TofuStore {
+    hosts: HashMap<String, KnownHost>,
+}
+
+impl TofuStore {
+    pub fn new() -> TofuStore {
+        TofuStore::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> TofuStore {
+        File::open(path)
+            .ok()
+            .and_then(|reader| bincode::deserialize_from(reader).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        bincode::serialize_into(file, self)?;
+        Ok(())
+    }
+
+    /// Check `leaf_der` against whatever is pinned for `host_port`, trusting it
+    /// on first contact and allowing a replacement once the pinned certificate
+    /// has expired. Rejects a mismatched, still-valid certificate.
+    fn check(&mut self, host_port: &str, leaf_der: &[u8], not_after: i64) -> Result<(), String> {
+        let fingerprint: [u8; 32] = Sha256::digest(leaf_der).into();
+        match self.hosts.get(host_port) {
+            Some(known) if known.fingerprint == fingerprint => Ok(()),
+            Some(known) if known.not_after < now() => {
+                self.hosts
+                    .insert(host_port.to_string(), KnownHost { fingerprint, not_after });
+                Ok(())
+            }
+            Some(_) => Err(format!(
+                "certificate fingerprint for {host_port} changed and the pinned certificate hasn't expired"
+            )),
+            None => {
+                self.hosts
+                    .insert(host_port.to_string(), KnownHost { fingerprint, not_after });
+                Ok(())
+            }
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn not_after_timestamp(der: &[u8]) -> Result<i64, Box<dyn Error>> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)?;
+    Ok(cert.validity().not_after.timestamp())
+}
+
+/// A [`ServerCertVerifier`] that pins the leaf certificate of `host_port` in a
+/// shared [`TofuStore`] instead of validating against a CA chain, matching how
+/// Gemini capsules are expected to be self-signed.
+///
+/// `ServerCertVerifier`/`ServerCertVerified` live behind rustls's
+/// `dangerous_configuration` feature, so any `Cargo.toml` for this crate must
+/// depend on `rustls` with `features = ["dangerous_configuration"]`.
+pub struct TofuVerifier {
+    store: Arc<std::sync::Mutex<TofuStore>>,
+    host_port: String,
+}
+
+impl TofuVerifier {
+    pub fn new(store: Arc<std::sync::Mutex<TofuStore>>, host_port: String) -> TofuVerifier {
+        TofuVerifier { store, host_port }
+    }
+}
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        let not_after = not_after_timestamp(&end_entity.0)
+            .map_err(|e| TlsError::General(format!("couldn't parse leaf certificate: {e}")))?;
+        self.store
+            .lock()
+            .unwrap()
+            .check(&self.host_port, &end_entity.0, not_after)
+            .map_err(TlsError::General)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusts_on_first_contact() {
+        let mut store = TofuStore::new();
+        assert!(store.check("host:1965", b"leaf-cert", now() + 3600).is_ok());
+    }
+
+    #[test]
+    fn accepts_the_same_certificate_again() {
+        let mut store = TofuStore::new();
+        store.check("host:1965", b"leaf-cert", now() + 3600).unwrap();
+        assert!(store.check("host:1965", b"leaf-cert", now() + 3600).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_mismatched_still_valid_certificate() {
+        let mut store = TofuStore::new();
+        store.check("host:1965", b"leaf-cert", now() + 3600).unwrap();
+        assert!(store.check("host:1965", b"other-cert", now() + 3600).is_err());
+    }
+
+    #[test]
+    fn allows_replacement_once_the_pinned_certificate_has_expired() {
+        let mut store = TofuStore::new();
+        store.check("host:1965", b"leaf-cert", now() - 1).unwrap();
+        assert!(store.check("host:1965", b"other-cert", now() + 3600).is_ok());
+    }
+}